@@ -3,34 +3,42 @@
 
 //! Utilities for working with datafusion execution plans
 
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
 
 use arrow_array::RecordBatch;
-use arrow_schema::Schema as ArrowSchema;
+use arrow_schema::{Schema as ArrowSchema, SchemaRef};
 use datafusion::{
     catalog::streaming::StreamingTable,
     dataframe::DataFrame,
     execution::{
         context::{SessionConfig, SessionContext},
         disk_manager::DiskManagerConfig,
-        memory_pool::FairSpillPool,
+        memory_pool::{
+            FairSpillPool, GreedyMemoryPool, MemoryConsumer, MemoryPool, MemoryReservation,
+        },
         runtime_env::RuntimeEnvBuilder,
         TaskContext,
     },
     physical_plan::{
         analyze::AnalyzeExec,
+        coalesce_partitions::CoalescePartitionsExec,
         display::DisplayableExecutionPlan,
         execution_plan::{Boundedness, EmissionType},
+        metrics::{BaselineMetrics, ExecutionPlanMetricsSet, MetricsSet},
         stream::RecordBatchStreamAdapter,
         streaming::PartitionStream,
-        DisplayAs, DisplayFormatType, ExecutionPlan, PlanProperties, SendableRecordBatchStream,
+        DisplayAs, DisplayFormatType, ExecutionPlan, PlanProperties, RecordBatchStream,
+        SendableRecordBatchStream,
     },
 };
 use datafusion_common::{DataFusionError, Statistics};
 use datafusion_physical_expr::{EquivalenceProperties, Partitioning};
 use lazy_static::lazy_static;
 
-use futures::{stream, StreamExt};
+use futures::{stream, Stream, StreamExt};
 use lance_arrow::SchemaExt;
 use lance_core::{
     utils::{
@@ -60,6 +68,7 @@ pub struct OneShotExec {
     // can still function after exhausted
     schema: Arc<ArrowSchema>,
     properties: PlanProperties,
+    metrics: ExecutionPlanMetricsSet,
 }
 
 impl OneShotExec {
@@ -75,6 +84,7 @@ impl OneShotExec {
                 EmissionType::Incremental,
                 Boundedness::Bounded,
             ),
+            metrics: ExecutionPlanMetricsSet::new(),
         }
     }
 
@@ -144,14 +154,25 @@ impl ExecutionPlan for OneShotExec {
 
     fn with_new_children(
         self: Arc<Self>,
-        _children: Vec<Arc<dyn ExecutionPlan>>,
+        children: Vec<Arc<dyn ExecutionPlan>>,
     ) -> datafusion_common::Result<Arc<dyn ExecutionPlan>> {
-        todo!()
+        // OneShotExec is always a leaf, so there is nothing to swap out.  We still need
+        // a real implementation (instead of `todo!()`) because plans rooted at a
+        // OneShotExec can now be wrapped in a CoalescePartitionsExec by `execute_plan`,
+        // and datafusion's optimizer rules may call `with_new_children` while rewriting
+        // that merged tree.
+        if children.is_empty() {
+            Ok(self)
+        } else {
+            Err(DataFusionError::Internal(
+                "OneShotExec does not accept any children".to_string(),
+            ))
+        }
     }
 
     fn execute(
         &self,
-        _partition: usize,
+        partition: usize,
         _context: Arc<datafusion::execution::TaskContext>,
     ) -> datafusion_common::Result<SendableRecordBatchStream> {
         let stream = self
@@ -160,7 +181,11 @@ impl ExecutionPlan for OneShotExec {
             .map_err(|err| DataFusionError::Execution(err.to_string()))?
             .take();
         if let Some(stream) = stream {
-            Ok(stream)
+            let baseline_metrics = BaselineMetrics::new(&self.metrics, partition);
+            Ok(Box::pin(MetricsStream {
+                inner: stream,
+                baseline_metrics,
+            }))
         } else {
             Err(DataFusionError::Execution(
                 "OneShotExec has already been executed".to_string(),
@@ -175,6 +200,37 @@ impl ExecutionPlan for OneShotExec {
     fn properties(&self) -> &datafusion::physical_plan::PlanProperties {
         &self.properties
     }
+
+    fn metrics(&self) -> Option<MetricsSet> {
+        Some(self.metrics.clone_inner())
+    }
+}
+
+/// Wraps a [`SendableRecordBatchStream`] so that rows and elapsed compute time are
+/// recorded against a [`BaselineMetrics`] as batches flow through, mirroring how other
+/// stream-sourced execution nodes have their streams retrofitted with metrics.
+struct MetricsStream {
+    inner: SendableRecordBatchStream,
+    baseline_metrics: BaselineMetrics,
+}
+
+impl Stream for MetricsStream {
+    type Item = datafusion_common::Result<RecordBatch>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let poll = {
+            let _timer = this.baseline_metrics.elapsed_compute().timer();
+            this.inner.as_mut().poll_next(cx)
+        };
+        this.baseline_metrics.record_poll(poll)
+    }
+}
+
+impl RecordBatchStream for MetricsStream {
+    fn schema(&self) -> SchemaRef {
+        self.inner.schema()
+    }
 }
 
 /// Callback for reporting statistics after a scan
@@ -187,6 +243,61 @@ pub struct LanceExecutionOptions {
     pub batch_size: Option<usize>,
     pub target_partition: Option<usize>,
     pub execution_stats_callback: Option<ExecutionStatsCallback>,
+    /// When `use_spilling` is also set, spill files requested through
+    /// [`create_spill_file`] are written with `O_DIRECT` in
+    /// [`DEFAULT_DIRECT_IO_ALIGNMENT`]-byte (or `direct_io_alignment`) aligned blocks
+    /// instead of going through the OS page cache, falling back to buffered I/O on
+    /// filesystems that reject `O_DIRECT`.
+    ///
+    /// This only affects spill files allocated via [`create_spill_file`]. DataFusion's
+    /// built-in physical operators (e.g. `SortExec`, hash joins/aggregates) allocate
+    /// their own spill files directly against the runtime's `DiskManager` and do not
+    /// consult this option — DataFusion does not currently expose a way to substitute
+    /// their spill I/O with a custom writer.
+    pub direct_io_spill: bool,
+    /// Block alignment, in bytes, used for direct I/O spill writes. Defaults to
+    /// [`DEFAULT_DIRECT_IO_ALIGNMENT`] when unset. Has no effect unless `direct_io_spill`
+    /// is set.
+    pub direct_io_alignment: Option<usize>,
+    /// Which kind of memory pool to use when `use_spilling` is set. When unset, the
+    /// `LANCE_MEM_POOL_KIND` environment variable (`fair` or `greedy`) is used if
+    /// present, otherwise [`MemPoolKind::Fair`], which shares the budget evenly across concurrent
+    /// operators. [`MemPoolKind::Greedy`] serves requests first-come-first-served,
+    /// which better suits a single large operator (e.g. PQ codebook training) that
+    /// legitimately needs most of the budget at once.
+    pub mem_pool_kind: Option<MemPoolKind>,
+}
+
+/// The kind of [`MemoryPool`] to install when spilling is enabled.
+///
+/// Fair pools divide the budget evenly across concurrent operators, which suits scans
+/// and index lookups with many small consumers. Greedy pools hand out memory
+/// first-come-first-served, which suits workloads dominated by a single large
+/// allocation (e.g. training a PQ codebook) that would otherwise be starved by a fair
+/// split.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MemPoolKind {
+    #[default]
+    Fair,
+    Greedy,
+}
+
+impl std::str::FromStr for MemPoolKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "fair" => Ok(Self::Fair),
+            "greedy" => Ok(Self::Greedy),
+            _ => Err(Error::invalid_input(
+                format!(
+                    "Invalid value for LANCE_MEM_POOL_KIND: {}, expected fair or greedy",
+                    s
+                ),
+                location!(),
+            )),
+        }
+    }
 }
 
 impl std::fmt::Debug for LanceExecutionOptions {
@@ -200,6 +311,9 @@ impl std::fmt::Debug for LanceExecutionOptions {
                 "execution_stats_callback",
                 &self.execution_stats_callback.is_some(),
             )
+            .field("direct_io_spill", &self.direct_io_spill)
+            .field("direct_io_alignment", &self.direct_io_alignment)
+            .field("mem_pool_kind", &self.mem_pool_kind)
             .finish()
     }
 }
@@ -232,6 +346,92 @@ impl LanceExecutionOptions {
             })
             .unwrap_or(true)
     }
+
+    /// Block alignment, in bytes, to use for direct I/O spill writes.
+    pub fn direct_io_alignment(&self) -> usize {
+        self.direct_io_alignment
+            .unwrap_or(crate::spill::DEFAULT_DIRECT_IO_ALIGNMENT)
+    }
+
+    pub fn mem_pool_kind(&self) -> MemPoolKind {
+        self.mem_pool_kind.unwrap_or_else(|| {
+            std::env::var("LANCE_MEM_POOL_KIND")
+                .ok()
+                .and_then(|s| match s.parse::<MemPoolKind>() {
+                    Ok(kind) => Some(kind),
+                    Err(e) => {
+                        warn!("Failed to parse LANCE_MEM_POOL_KIND: {}, using default", e);
+                        None
+                    }
+                })
+                .unwrap_or_default()
+        })
+    }
+}
+
+/// Wraps a [`MemoryPool`] and records the high-water mark of its `reserved()` value, so
+/// callers can read back a true peak instead of a point-in-time sample — which, taken
+/// after a plan has finished and released its reservations, would just read back ~0.
+///
+/// Note that when this wraps a pool shared across concurrent plan executions (as the
+/// cached `DEFAULT_SESSION_CONTEXT_WITH_SPILLING_*` contexts do, see
+/// [`get_session_context`]), the recorded peak is a high-water mark across *all* plans
+/// sharing that pool, not just the one being reported on.
+#[derive(Debug)]
+struct PeakTrackingMemoryPool {
+    inner: Arc<dyn MemoryPool>,
+    peak: AtomicUsize,
+}
+
+impl PeakTrackingMemoryPool {
+    fn new(inner: Arc<dyn MemoryPool>) -> Self {
+        Self {
+            inner,
+            peak: AtomicUsize::new(0),
+        }
+    }
+
+    fn record_high_water_mark(&self) {
+        let reserved = self.inner.reserved();
+        self.peak.fetch_max(reserved, Ordering::Relaxed);
+    }
+
+    fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+}
+
+impl MemoryPool for PeakTrackingMemoryPool {
+    fn register(&self, consumer: &MemoryConsumer) {
+        self.inner.register(consumer)
+    }
+
+    fn unregister(&self, consumer: &MemoryConsumer) {
+        self.inner.unregister(consumer)
+    }
+
+    fn grow(&self, reservation: &MemoryReservation, additional: usize) {
+        self.inner.grow(reservation, additional);
+        self.record_high_water_mark();
+    }
+
+    fn shrink(&self, reservation: &MemoryReservation, size: usize) {
+        self.inner.shrink(reservation, size)
+    }
+
+    fn try_grow(
+        &self,
+        reservation: &MemoryReservation,
+        additional: usize,
+    ) -> datafusion_common::Result<()> {
+        self.inner.try_grow(reservation, additional)?;
+        self.record_high_water_mark();
+        Ok(())
+    }
+
+    fn reserved(&self) -> usize {
+        self.inner.reserved()
+    }
 }
 
 pub fn new_session_context(options: &LanceExecutionOptions) -> SessionContext {
@@ -241,32 +441,95 @@ pub fn new_session_context(options: &LanceExecutionOptions) -> SessionContext {
         session_config = session_config.with_target_partitions(target_partition);
     }
     if options.use_spilling() {
+        if options.direct_io_spill {
+            debug!(
+                "Direct I/O spill enabled with {}-byte alignment for spill files created \
+                 via create_spill_file(); DataFusion's own physical operators allocate \
+                 their spill files separately and are unaffected",
+                options.direct_io_alignment()
+            );
+        }
+        let mem_pool: Arc<dyn MemoryPool> = match options.mem_pool_kind() {
+            MemPoolKind::Fair => Arc::new(FairSpillPool::new(options.mem_pool_size() as usize)),
+            MemPoolKind::Greedy => {
+                Arc::new(GreedyMemoryPool::new(options.mem_pool_size() as usize))
+            }
+        };
+        let peak_tracking_pool = Arc::new(PeakTrackingMemoryPool::new(mem_pool));
+        // Stashed as a config extension (rather than returned separately) so this
+        // function's signature stays unchanged; `report_plan_summary_metrics` reads it
+        // back out of the `SessionContext` it was installed on.
+        session_config = session_config.with_extension(peak_tracking_pool.clone());
         runtime_env_builder = runtime_env_builder
             .with_disk_manager(DiskManagerConfig::new())
-            .with_memory_pool(Arc::new(FairSpillPool::new(
-                options.mem_pool_size() as usize
-            )));
+            .with_memory_pool(peak_tracking_pool);
     }
     let runtime_env = runtime_env_builder.build_arc().unwrap();
     SessionContext::new_with_config_rt(session_config, runtime_env)
 }
 
+/// Allocates a spill file for `session_ctx`, honoring
+/// [`LanceExecutionOptions::direct_io_spill`].
+///
+/// Lance's own spilling code (e.g. external sort/merge during index builds) should
+/// request spill files through this function, rather than opening a plain `File`
+/// itself, so the write goes through [`crate::spill::DirectIoSpillWriter`] (with its
+/// `O_DIRECT` alignment and buffered-I/O fallback) while still allocating the
+/// underlying temp file via `session_ctx`'s `DiskManager`, so it lands in the configured
+/// spill directories. Bytes written through the returned writer are not reported to the
+/// `DiskManager` and do not count toward its disk-usage limit.
+///
+/// Note this only covers spill files requested through this function. DataFusion's
+/// built-in physical operators (`SortExec`, hash joins/aggregates, ...) allocate their
+/// spill files directly against the `DiskManager` and are not routed through
+/// [`crate::spill::DirectIoSpillWriter`] — DataFusion does not expose a way to
+/// substitute their spill I/O with a custom writer.
+pub fn create_spill_file(
+    session_ctx: &SessionContext,
+    options: &LanceExecutionOptions,
+    request_description: &str,
+) -> Result<crate::spill::ManagedSpillFile> {
+    crate::spill::create_spill_file(
+        &session_ctx.runtime_env().disk_manager,
+        options.use_spilling() && options.direct_io_spill,
+        options.direct_io_alignment(),
+        request_description,
+    )
+}
+
 lazy_static! {
     static ref DEFAULT_SESSION_CONTEXT: SessionContext =
         new_session_context(&LanceExecutionOptions::default());
-    static ref DEFAULT_SESSION_CONTEXT_WITH_SPILLING: SessionContext = {
+    static ref DEFAULT_SESSION_CONTEXT_WITH_SPILLING_FAIR: SessionContext = {
+        new_session_context(&LanceExecutionOptions {
+            use_spilling: true,
+            mem_pool_kind: Some(MemPoolKind::Fair),
+            ..Default::default()
+        })
+    };
+    static ref DEFAULT_SESSION_CONTEXT_WITH_SPILLING_GREEDY: SessionContext = {
         new_session_context(&LanceExecutionOptions {
             use_spilling: true,
+            mem_pool_kind: Some(MemPoolKind::Greedy),
             ..Default::default()
         })
     };
 }
 
+/// Whether [`get_session_context`] will hand back one of the cached, process-wide
+/// `DEFAULT_SESSION_CONTEXT*` statics for `options` (shared with every other call with
+/// matching options) rather than building a fresh, exclusively-owned one.
+fn uses_shared_session_context(options: &LanceExecutionOptions) -> bool {
+    options.mem_pool_size() == DEFAULT_LANCE_MEM_POOL_SIZE && options.target_partition.is_none()
+}
+
 pub fn get_session_context(options: &LanceExecutionOptions) -> SessionContext {
-    if options.mem_pool_size() == DEFAULT_LANCE_MEM_POOL_SIZE && options.target_partition.is_none()
-    {
+    if uses_shared_session_context(options) {
         return if options.use_spilling() {
-            DEFAULT_SESSION_CONTEXT_WITH_SPILLING.clone()
+            match options.mem_pool_kind() {
+                MemPoolKind::Fair => DEFAULT_SESSION_CONTEXT_WITH_SPILLING_FAIR.clone(),
+                MemPoolKind::Greedy => DEFAULT_SESSION_CONTEXT_WITH_SPILLING_GREEDY.clone(),
+            }
         } else {
             DEFAULT_SESSION_CONTEXT.clone()
         };
@@ -286,6 +549,11 @@ fn get_task_context(
     state.task_ctx()
 }
 
+/// DataFusion's standard metric name for the number of times a node spilled to disk.
+const SPILL_COUNT_METRIC: &str = "spill_count";
+/// DataFusion's standard metric name for the number of bytes written to on-disk spill files.
+const SPILLED_BYTES_METRIC: &str = "spilled_bytes";
+
 #[derive(Default)]
 pub struct ExecutionSummaryCounts {
     pub iops: usize,
@@ -294,6 +562,35 @@ pub struct ExecutionSummaryCounts {
     pub indices_loaded: usize,
     pub parts_loaded: usize,
     pub index_comparisons: usize,
+    /// Number of times any node in the plan spilled intermediate state to disk.
+    pub spill_count: usize,
+    /// Total number of bytes written to on-disk spill files across the whole plan.
+    pub spilled_bytes: usize,
+    /// High-water mark, in bytes, of memory reserved from the execution memory pool over
+    /// the lifetime of the `SessionContext` the plan ran under.
+    ///
+    /// This is tracked continuously (via [`PeakTrackingMemoryPool`]) rather than sampled
+    /// after the plan finishes, so it reflects a true peak rather than the ~0 a
+    /// post-completion sample of a drained pool would report. It is `None` when the plan
+    /// was run without a dedicated memory pool (i.e. spilling disabled).
+    ///
+    /// See [`Self::peak_mem_reserved_is_shared`] before treating this as *this plan's*
+    /// peak — it may reflect a pool shared with other concurrent plans.
+    pub peak_mem_reserved: Option<usize>,
+    /// `true` if [`Self::peak_mem_reserved`] comes from a memory pool shared with other,
+    /// possibly-concurrent plan executions, rather than one exclusively owned by this
+    /// call to [`execute_plan`]/[`analyze_plan`].
+    ///
+    /// [`get_session_context`]'s fast path reuses one of a handful of process-wide
+    /// cached session contexts (see `DEFAULT_SESSION_CONTEXT_WITH_SPILLING_*`) whenever
+    /// `options` requests the default memory pool size and no custom target partition
+    /// count, trading per-plan isolation for avoiding the cost of rebuilding a
+    /// `SessionContext` on every call. In that case `peak_mem_reserved` is a high-water
+    /// mark across every plan that has run on the shared pool, not this one — a
+    /// per-plan memory alert should either ignore the value when this is `true`, or
+    /// pass non-default [`LanceExecutionOptions`] (e.g. a custom `mem_pool_size`) to get
+    /// an exclusively-owned pool. Always `false` when `peak_mem_reserved` is `None`.
+    pub peak_mem_reserved_is_shared: bool,
 }
 
 fn visit_node(node: &dyn ExecutionPlan, counts: &mut ExecutionSummaryCounts) {
@@ -322,19 +619,40 @@ fn visit_node(node: &dyn ExecutionPlan, counts: &mut ExecutionSummaryCounts) {
             .find_count(INDEX_COMPARISONS_METRIC)
             .map(|c| c.value())
             .unwrap_or(0);
+        // Spilling may happen locally (e.g. a sort/join spilling to this node's own temp
+        // files) as well as further down the tree; these are summed across all nodes into
+        // plan-wide totals, which do not record which node did the spilling.
+        counts.spill_count += metrics
+            .find_count(SPILL_COUNT_METRIC)
+            .map(|c| c.value())
+            .unwrap_or(0);
+        counts.spilled_bytes += metrics
+            .find_count(SPILLED_BYTES_METRIC)
+            .map(|c| c.value())
+            .unwrap_or(0);
     }
     for child in node.children() {
         visit_node(child.as_ref(), counts);
     }
 }
 
-fn report_plan_summary_metrics(plan: &dyn ExecutionPlan, options: &LanceExecutionOptions) {
+fn report_plan_summary_metrics(
+    plan: &dyn ExecutionPlan,
+    options: &LanceExecutionOptions,
+    memory_pool: Option<&Arc<PeakTrackingMemoryPool>>,
+    peak_mem_reserved_is_shared: bool,
+) {
     let output_rows = plan
         .metrics()
         .map(|m| m.output_rows().unwrap_or(0))
         .unwrap_or(0);
     let mut counts = ExecutionSummaryCounts::default();
     visit_node(plan, &mut counts);
+    // The in-memory high-water mark comes from the pool rather than from plan metrics,
+    // since it reflects the pool's tracked peak rather than any single node's.
+    counts.peak_mem_reserved = memory_pool.map(|pool| pool.peak());
+    counts.peak_mem_reserved_is_shared =
+        counts.peak_mem_reserved.is_some() && peak_mem_reserved_is_shared;
     tracing::info!(
         target: TRACE_EXECUTION,
         type = EXECUTION_PLAN_RUN,
@@ -345,15 +663,35 @@ fn report_plan_summary_metrics(plan: &dyn ExecutionPlan, options: &LanceExecutio
         indices_loaded = counts.indices_loaded,
         parts_loaded = counts.parts_loaded,
         index_comparisons = counts.index_comparisons,
+        spill_count = counts.spill_count,
+        spilled_bytes = counts.spilled_bytes,
+        peak_mem_reserved = ?counts.peak_mem_reserved,
+        peak_mem_reserved_is_shared = counts.peak_mem_reserved_is_shared,
     );
     if let Some(callback) = options.execution_stats_callback.as_ref() {
         callback(&counts);
     }
 }
 
+/// Wraps `plan` in a [`CoalescePartitionsExec`] if it reports more than one output
+/// partition, so that callers always get a single merged stream back.
+///
+/// The coalesce node spawns one task per input partition against the shared
+/// `TaskContext` and funnels their batches into a single output stream, so no data
+/// is lost and callers no longer need to add their own merge node.
+fn merge_partitions_if_needed(plan: Arc<dyn ExecutionPlan>) -> Arc<dyn ExecutionPlan> {
+    if plan.properties().partitioning.partition_count() > 1 {
+        Arc::new(CoalescePartitionsExec::new(plan))
+    } else {
+        plan
+    }
+}
+
 /// Executes a plan using default session & runtime configuration
 ///
-/// Only executes a single partition.  Panics if the plan has more than one partition.
+/// If the plan reports more than one output partition (e.g. it was built with
+/// `LanceExecutionOptions::target_partition` > 1) the partitions are merged into a
+/// single stream via a coalesce node before being returned.
 pub fn execute_plan(
     plan: Arc<dyn ExecutionPlan>,
     options: LanceExecutionOptions,
@@ -364,15 +702,28 @@ pub fn execute_plan(
     );
 
     let session_ctx = get_session_context(&options);
-
-    // NOTE: we are only executing the first partition here. Therefore, if
-    // the plan has more than one partition, we will be missing data.
-    assert_eq!(plan.properties().partitioning.partition_count(), 1);
-    let stream = plan.execute(0, get_task_context(&session_ctx, &options))?;
+    // `Arc<dyn MemoryPool>` can't be downcast back to `PeakTrackingMemoryPool`, so the
+    // concrete pool is stashed as a config extension at construction time (see
+    // `new_session_context`) and retrieved here instead of going through
+    // `session_ctx.runtime_env().memory_pool`.
+    let memory_pool = session_ctx
+        .state()
+        .config()
+        .get_extension::<PeakTrackingMemoryPool>();
+    let peak_mem_reserved_is_shared = uses_shared_session_context(&options);
+    let root = merge_partitions_if_needed(plan);
+
+    assert_eq!(root.properties().partitioning.partition_count(), 1);
+    let stream = root.execute(0, get_task_context(&session_ctx, &options))?;
 
     let schema = stream.schema();
     let stream = stream.finally(move || {
-        report_plan_summary_metrics(plan.as_ref(), &options);
+        report_plan_summary_metrics(
+            root.as_ref(),
+            &options,
+            memory_pool.as_ref(),
+            peak_mem_reserved_is_shared,
+        );
     });
     Ok(Box::pin(RecordBatchStreamAdapter::new(schema, stream)))
 }
@@ -382,6 +733,7 @@ pub async fn analyze_plan(
     options: LanceExecutionOptions,
 ) -> Result<String> {
     let schema = plan.schema();
+    let plan = merge_partitions_if_needed(plan);
     let analyze = Arc::new(AnalyzeExec::new(true, true, plan, schema));
 
     let session_ctx = get_session_context(&options);
@@ -461,3 +813,42 @@ impl SessionContextExt for SessionContext {
         self.read_table(Arc::new(provider))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write as _;
+
+    #[test]
+    fn test_create_spill_file_honors_direct_io_option() {
+        let options = LanceExecutionOptions {
+            use_spilling: true,
+            direct_io_spill: true,
+            ..Default::default()
+        };
+        let session_ctx = new_session_context(&options);
+        let mut spill = create_spill_file(&session_ctx, &options, "test spill").unwrap();
+        spill.writer.write_all(b"hello").unwrap();
+        let path = spill.path().to_path_buf();
+        spill.writer.close().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_uses_shared_session_context() {
+        // Default options hit the cached, process-wide session contexts.
+        assert!(uses_shared_session_context(
+            &LanceExecutionOptions::default()
+        ));
+        // Any deviation from the default pool size or partition count gets its own
+        // exclusively-owned `SessionContext`/memory pool instead.
+        assert!(!uses_shared_session_context(&LanceExecutionOptions {
+            mem_pool_size: Some(123),
+            ..Default::default()
+        }));
+        assert!(!uses_shared_session_context(&LanceExecutionOptions {
+            target_partition: Some(4),
+            ..Default::default()
+        }));
+    }
+}
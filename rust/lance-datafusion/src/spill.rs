@@ -0,0 +1,434 @@
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-FileCopyrightText: Copyright The Lance Authors
+
+//! Direct I/O (`O_DIRECT`) spill writer used when [`LanceExecutionOptions::direct_io_spill`]
+//! is enabled.
+//!
+//! Spill files written through the OS page cache double-buffer data that is only ever
+//! read back once, and during large index builds they can evict hot pages that the rest
+//! of the build still needs. This writer buffers output into an alignment-sized staging
+//! block and flushes full blocks with `O_DIRECT`, bypassing the page cache entirely.
+//!
+//! [`LanceExecutionOptions::direct_io_spill`]: crate::exec::LanceExecutionOptions::direct_io_spill
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use datafusion::execution::disk_manager::{DiskManager, RefCountedTempFile};
+
+use lance_core::{Error, Result};
+use snafu::location;
+
+/// Default alignment, in bytes, used for direct I/O spill writes.
+///
+/// 4096 matches the block size of essentially all modern storage, which `O_DIRECT`
+/// requires the offset, length, *and buffer address* of every write to be aligned to.
+pub const DEFAULT_DIRECT_IO_ALIGNMENT: usize = 4096;
+
+/// A byte buffer whose backing allocation always has room for a window starting at an
+/// address aligned to `align` bytes, as `O_DIRECT` requires of the source buffer (not
+/// just the file offset/length). Implemented by over-allocating a plain `Vec<u8>` and
+/// writing into an aligned offset within it, rather than a custom allocator.
+///
+/// The buffer never grows past its initial capacity, so the aligned window is stable
+/// for the buffer's lifetime (a `Vec` reallocation would otherwise invalidate it).
+struct AlignedBuffer {
+    raw: Vec<u8>,
+    offset: usize,
+    len: usize,
+    capacity: usize,
+}
+
+impl AlignedBuffer {
+    fn new(align: usize, capacity: usize) -> Self {
+        let raw = vec![0u8; capacity + align];
+        let addr = raw.as_ptr() as usize;
+        let offset = (align - (addr % align)) % align;
+        Self {
+            raw,
+            offset,
+            len: 0,
+            capacity,
+        }
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.raw[self.offset..self.offset + self.len]
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Appends as much of `data` as fits before the buffer reaches `capacity`, returning
+    /// the number of bytes consumed.
+    fn extend_from_slice(&mut self, data: &[u8]) -> usize {
+        let room = self.capacity() - self.len;
+        let n = room.min(data.len());
+        let start = self.offset + self.len;
+        self.raw[start..start + n].copy_from_slice(&data[..n]);
+        self.len += n;
+        n
+    }
+
+    /// Zero-pads the buffer up to `capacity`.
+    fn pad_to_capacity(&mut self) {
+        let cap = self.capacity();
+        for b in &mut self.raw[self.offset + self.len..self.offset + cap] {
+            *b = 0;
+        }
+        self.len = cap;
+    }
+}
+
+/// A [`Write`] implementation that writes a spill file using `O_DIRECT`, staging data
+/// into an `alignment`-sized buffer and only issuing a direct write once a full block
+/// has accumulated.
+///
+/// The logical length (what the caller actually wrote) is tracked separately from the
+/// padded, on-disk length, and `close` truncates the file back to the logical length so
+/// readers see exactly the bytes that were written. `O_DIRECT` can be rejected either at
+/// `open()` (some overlay/network filesystems refuse the flag outright) or at `write()`
+/// time (some accept the flag but reject an unaligned buffer or length); both cases fall
+/// back to plain buffered I/O rather than failing the spill.
+pub struct DirectIoSpillWriter {
+    file: File,
+    path: PathBuf,
+    alignment: usize,
+    staging: AlignedBuffer,
+    logical_len: u64,
+    on_disk_len: u64,
+    buffered_fallback: bool,
+    closed: bool,
+    /// Whether `Drop` should remove `path` if the writer was never `close`d. This is
+    /// false when the underlying file is owned by a [`RefCountedTempFile`] (see
+    /// [`create_spill_file`]), since that handle already manages deletion.
+    owns_path: bool,
+}
+
+impl DirectIoSpillWriter {
+    /// Creates a new direct I/O spill writer backed by a fresh file at `path`.
+    pub fn create(path: impl AsRef<Path>, alignment: usize) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let (file, buffered_fallback) = match Self::open_direct(&path) {
+            Ok(file) => (file, false),
+            Err(_) => (Self::open_buffered(&path)?, true),
+        };
+        Ok(Self::from_file(file, path, alignment, buffered_fallback))
+    }
+
+    /// Creates a writer that always uses plain buffered I/O, e.g. when
+    /// [`LanceExecutionOptions::direct_io_spill`](crate::exec::LanceExecutionOptions::direct_io_spill)
+    /// is disabled.
+    pub fn create_buffered(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = Self::open_buffered(&path)?;
+        Ok(Self::from_file(
+            file,
+            path,
+            DEFAULT_DIRECT_IO_ALIGNMENT,
+            true,
+        ))
+    }
+
+    fn from_file(file: File, path: PathBuf, alignment: usize, buffered_fallback: bool) -> Self {
+        Self {
+            file,
+            path,
+            alignment,
+            staging: AlignedBuffer::new(alignment, alignment),
+            logical_len: 0,
+            on_disk_len: 0,
+            buffered_fallback,
+            closed: false,
+            owns_path: true,
+        }
+    }
+
+    fn open_direct(path: &Path) -> io::Result<File> {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(path)
+    }
+
+    fn open_buffered(path: &Path) -> io::Result<File> {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+    }
+
+    /// True if this writer uses buffered I/O: it was made by [`Self::create_buffered`],
+    /// `O_DIRECT` was rejected at `open()` time, or a later write hit `EINVAL`.
+    pub fn is_buffered_fallback(&self) -> bool {
+        self.buffered_fallback
+    }
+
+    /// The exact number of bytes written by the caller, not counting any alignment
+    /// padding written to satisfy `O_DIRECT`.
+    pub fn logical_len(&self) -> u64 {
+        self.logical_len
+    }
+
+    /// Writes `block` (which must be exactly `self.alignment` bytes, aligned, when using
+    /// `O_DIRECT`) to the file. If the filesystem rejects the write with `EINVAL`, falls
+    /// back to buffered I/O and retries so no data is lost.
+    fn write_block(&mut self, len_hint: u64) -> io::Result<()> {
+        match self.file.write_all(self.staging.as_slice()) {
+            Ok(()) => {
+                self.on_disk_len += self.staging.len() as u64;
+                Ok(())
+            }
+            Err(err) if !self.buffered_fallback && err.raw_os_error() == Some(libc::EINVAL) => {
+                self.switch_to_buffered()?;
+                // Buffered writes only need the logical (unpadded) prefix of the block.
+                let logical = self.staging.as_slice()[..len_hint as usize].to_vec();
+                self.file.write_all(&logical)?;
+                self.on_disk_len += logical.len() as u64;
+                Ok(())
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Reopens `self.path` without `O_DIRECT`, seeking past the bytes already written
+    /// durably via direct I/O, and marks this writer as using buffered I/O from now on.
+    fn switch_to_buffered(&mut self) -> io::Result<()> {
+        let mut buffered = Self::open_buffered(&self.path)?;
+        buffered.seek(SeekFrom::Start(self.on_disk_len))?;
+        self.file = buffered;
+        self.buffered_fallback = true;
+        Ok(())
+    }
+
+    /// Flushes any remaining staged bytes (padding the final `O_DIRECT` block if
+    /// needed), truncates the file to the exact logical length, and syncs it to disk.
+    pub fn close(mut self) -> io::Result<()> {
+        self.finish()?;
+        self.closed = true;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if !self.staging.is_empty() {
+            let logical_remainder = self.staging.len() as u64;
+            if !self.buffered_fallback {
+                self.staging.pad_to_capacity();
+            }
+            self.write_block(logical_remainder)?;
+            self.staging.clear();
+        }
+        self.file.set_len(self.logical_len)?;
+        self.file.sync_all()
+    }
+}
+
+impl Write for DirectIoSpillWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.logical_len += buf.len() as u64;
+        if self.buffered_fallback {
+            // No alignment requirement: write straight through to keep memory bounded.
+            return self.file.write_all(buf).map(|_| buf.len());
+        }
+
+        let mut remaining = buf;
+        while !remaining.is_empty() {
+            let consumed = self.staging.extend_from_slice(remaining);
+            remaining = &remaining[consumed..];
+            if self.staging.len() == self.staging.capacity() {
+                let full_block_len = self.staging.len() as u64;
+                self.write_block(full_block_len)?;
+                self.staging.clear();
+                if self.buffered_fallback {
+                    // write_block() just switched modes; flush whatever is left
+                    // without staging.
+                    if !remaining.is_empty() {
+                        self.file.write_all(remaining)?;
+                    }
+                    return Ok(buf.len());
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for DirectIoSpillWriter {
+    fn drop(&mut self) {
+        if self.closed || !self.owns_path {
+            return;
+        }
+        // Dropped without an explicit `close()` (e.g. an error partway through writing a
+        // spill batch). The file is incomplete and nobody else has a handle to it, so
+        // remove it rather than leaking an orphaned temp file.
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// A spill file obtained from a [`DiskManager`], so it is placed in the runtime's
+/// configured spill directories. Bytes written through `writer` are not reported to the
+/// [`DiskManager`] and do not count toward its disk-usage limit.
+///
+/// The [`RefCountedTempFile`] is kept alive for as long as the spill file should exist;
+/// dropping it removes the underlying file.
+pub struct ManagedSpillFile {
+    pub writer: DirectIoSpillWriter,
+    temp_file: RefCountedTempFile,
+}
+
+impl ManagedSpillFile {
+    pub fn path(&self) -> &Path {
+        self.temp_file.path()
+    }
+}
+
+/// Allocates a spill file through `disk_manager` and wraps it in a [`DirectIoSpillWriter`],
+/// using `O_DIRECT` when `direct_io_spill` is true and buffered I/O otherwise. Only spill
+/// files requested through this function are affected; DataFusion's own operators
+/// allocate theirs from the [`DiskManager`] directly.
+pub fn create_spill_file(
+    disk_manager: &DiskManager,
+    direct_io_spill: bool,
+    alignment: usize,
+    request_description: &str,
+) -> Result<ManagedSpillFile> {
+    let temp_file = disk_manager
+        .create_tmp_file(request_description)
+        .map_err(|err| Error::io(err.to_string(), location!()))?;
+    let mut writer = if direct_io_spill {
+        DirectIoSpillWriter::create(temp_file.path(), alignment)
+    } else {
+        DirectIoSpillWriter::create_buffered(temp_file.path())
+    }
+    .map_err(|err| Error::io(err.to_string(), location!()))?;
+    // The temp file handle, not the writer, owns this path's lifecycle.
+    writer.owns_path = false;
+    Ok(ManagedSpillFile { writer, temp_file })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aligned_buffer_capacity_is_one_block() {
+        // `DirectIoSpillWriter` stages exactly one block at a time
+        // (`AlignedBuffer::new(alignment, alignment)`), so `capacity()` must report
+        // exactly `alignment` bytes, not `alignment` plus whatever slop the backing
+        // `Vec` needed to find an aligned offset. A larger-than-`alignment` capacity
+        // would mean `write_block` is handed a block of the wrong size, which real
+        // `O_DIRECT` filesystems reject with `EINVAL`.
+        for align in [512usize, 4096] {
+            let buf = AlignedBuffer::new(align, align);
+            assert_eq!(buf.capacity(), align);
+        }
+    }
+
+    #[test]
+    fn test_aligned_buffer_start_address_is_aligned() {
+        let align = 4096;
+        let buf = AlignedBuffer::new(align, align);
+        let addr = buf.as_slice().as_ptr() as usize;
+        assert_eq!(addr % align, 0);
+    }
+
+    #[test]
+    fn test_full_block_writes_exactly_one_aligned_block_at_a_time() {
+        // Regression test for a bug where `AlignedBuffer::capacity()` returned more
+        // than `alignment` bytes, so a write of exactly `alignment` bytes never
+        // reached `staging.capacity()` and `write_block` was only ever called (with
+        // a wrongly-sized, padded block) from `finish()`. Here we write precisely
+        // `alignment` bytes per iteration and check that the writer's internal
+        // staging buffer is cleared after each one, i.e. a full aligned block was
+        // flushed immediately rather than accumulating past one block's worth.
+        let align = 512;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("spill-full-blocks");
+        let mut writer = DirectIoSpillWriter::create(&path, align).unwrap();
+        for i in 0..4u8 {
+            writer.write_all(&vec![i; align]).unwrap();
+            assert!(writer.staging.is_empty());
+        }
+        assert_eq!(writer.logical_len(), (4 * align) as u64);
+        writer.close().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap().len(), 4 * align);
+    }
+
+    #[test]
+    fn test_padding_is_truncated_away() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("spill-0");
+        let mut writer = DirectIoSpillWriter::create(&path, 512).unwrap();
+        let payload = vec![7u8; 1000];
+        writer.write_all(&payload).unwrap();
+        assert_eq!(writer.logical_len(), 1000);
+        writer.close().unwrap();
+
+        let on_disk = std::fs::read(&path).unwrap();
+        assert_eq!(on_disk.len(), 1000);
+        assert!(on_disk.iter().all(|b| *b == 7));
+    }
+
+    #[test]
+    fn test_multi_block_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("spill-multi");
+        let mut writer = DirectIoSpillWriter::create(&path, 512).unwrap();
+        // Several writes of varying size spanning many blocks.
+        for i in 0..20u8 {
+            writer.write_all(&vec![i; 137]).unwrap();
+        }
+        let expected_len = 20 * 137;
+        assert_eq!(writer.logical_len(), expected_len as u64);
+        writer.close().unwrap();
+
+        let on_disk = std::fs::read(&path).unwrap();
+        assert_eq!(on_disk.len(), expected_len);
+    }
+
+    #[test]
+    fn test_drop_without_close_removes_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("spill-1");
+        let writer = DirectIoSpillWriter::create(&path, 512).unwrap();
+        drop(writer);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_create_spill_file_uses_disk_manager() {
+        use datafusion::execution::disk_manager::DiskManagerConfig;
+
+        let disk_manager = DiskManager::try_new(DiskManagerConfig::new()).unwrap();
+        let mut spill = create_spill_file(&disk_manager, false, 512, "test spill").unwrap();
+        spill.writer.write_all(b"hello spill").unwrap();
+        let path = spill.path().to_path_buf();
+        spill.writer.close().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello spill");
+        // Dropping the managed temp file (not the writer) is what cleans it up.
+        drop(spill);
+        assert!(!path.exists());
+    }
+}